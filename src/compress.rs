@@ -0,0 +1,201 @@
+use std::io::Write;
+
+use crate::proc::Data;
+use crate::ResponseCode;
+
+/// Content-coding the server is willing to produce. Mirrors warp's compression filter.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Encoding {
+    Gzip,
+    Deflate,
+    Br,
+}
+
+impl Encoding {
+
+    fn header_value(&self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+            Encoding::Br => "br",
+        }
+    }
+
+    fn compress(&self, bytes: &[u8]) -> Vec<u8> {
+        match self {
+            Encoding::Gzip => {
+                let mut enc = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                enc.write_all(bytes).expect("compressing into a Vec cannot fail");
+                enc.finish().expect("compressing into a Vec cannot fail")
+            }
+            Encoding::Deflate => {
+                let mut enc = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+                enc.write_all(bytes).expect("compressing into a Vec cannot fail");
+                enc.finish().expect("compressing into a Vec cannot fail")
+            }
+            Encoding::Br => {
+                let mut out = Vec::new();
+                brotli::CompressorWriter::new(&mut out, 4096, 5, 22)
+                    .write_all(bytes)
+                    .expect("compressing into a Vec cannot fail");
+                out
+            }
+        }
+    }
+}
+
+/// Per-`RouteMatchGroup` compression policy: which encodings the server offers, in
+/// preference order, and the minimum response size worth compressing.
+#[derive(Debug, Clone)]
+pub struct CompressionConfig {
+
+    preferred: Vec<Encoding>,
+
+    min_size: usize,
+}
+
+impl CompressionConfig {
+
+    /// Offer `preferred` encodings, tried in the given order against the client's
+    /// `Accept-Encoding`. Responses smaller than 860 bytes are left uncompressed;
+    /// override with `min_size`.
+    pub fn new(preferred: Vec<Encoding>) -> Self {
+        CompressionConfig { preferred, min_size: 860 }
+    }
+
+    pub fn min_size(mut self, min_size: usize) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    fn negotiate(&self, accept_encoding: &str) -> Option<Encoding> {
+        let accepted: Vec<&str> = accept_encoding.split(',')
+            .map(|v| v.split(';').next().unwrap_or("").trim())
+            .collect();
+        self.preferred.iter()
+            .copied()
+            .find(|e| accepted.contains(&e.header_value()))
+    }
+
+    /// Compress `data` if `accept_encoding` (the request's `Accept-Encoding` header,
+    /// if any) names one of our preferred encodings and `data` is large enough to
+    /// be worth compressing; otherwise return it as-is.
+    pub fn apply(&self, accept_encoding: Option<&str>, data: Box<dyn Data>) -> Box<dyn Data> {
+        let accept_encoding = match accept_encoding {
+            Some(v) => v,
+            None => return data,
+        };
+        let encoding = match self.negotiate(accept_encoding) {
+            Some(e) => e,
+            None => return data,
+        };
+
+        let code = data.code();
+        let raw = data.into_bytes();
+        if raw.len() < self.min_size {
+            return Box::new(UncompressedData { code, bytes: raw });
+        }
+
+        Box::new(CompressedData {
+            code,
+            encoding,
+            bytes: encoding.compress(&raw),
+        })
+    }
+}
+
+/// `Data` produced by `CompressionConfig::apply` when `Content-Encoding` was negotiated.
+#[derive(Debug)]
+struct CompressedData {
+    code: ResponseCode,
+    encoding: Encoding,
+    bytes: Vec<u8>,
+}
+
+impl Data for CompressedData {
+
+    fn code(&self) -> ResponseCode {
+        self.code
+    }
+
+    fn into_bytes(self: Box<Self>) -> Vec<u8> {
+        self.bytes
+    }
+
+    fn content_encoding(&self) -> Option<&'static str> {
+        Some(self.encoding.header_value())
+    }
+}
+
+/// `Data` produced by `CompressionConfig::apply` when the original bytes were too
+/// small to bother compressing; carries the already-read bytes back along unchanged.
+#[derive(Debug)]
+struct UncompressedData {
+    code: ResponseCode,
+    bytes: Vec<u8>,
+}
+
+impl Data for UncompressedData {
+
+    fn code(&self) -> ResponseCode {
+        self.code
+    }
+
+    fn into_bytes(self: Box<Self>) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    struct Payload(Vec<u8>);
+
+    impl Data for Payload {
+
+        fn code(&self) -> ResponseCode {
+            200
+        }
+
+        fn into_bytes(self: Box<Self>) -> Vec<u8> {
+            self.0
+        }
+    }
+
+    #[test]
+    fn negotiates_the_first_preferred_encoding_the_client_accepts() {
+        let config = CompressionConfig::new(vec![Encoding::Br, Encoding::Gzip]);
+        assert_eq!(config.negotiate("gzip, deflate"), Some(Encoding::Gzip));
+        assert_eq!(config.negotiate("br;q=0.9, gzip;q=0.5"), Some(Encoding::Br));
+        assert_eq!(config.negotiate("deflate"), None);
+    }
+
+    #[test]
+    fn apply_leaves_response_untouched_without_an_accept_encoding_header() {
+        let config = CompressionConfig::new(vec![Encoding::Gzip]).min_size(0);
+        let data = config.apply(None, Box::new(Payload(vec![b'x'; 10])));
+        assert_eq!(data.code(), 200);
+        assert_eq!(data.content_encoding(), None);
+        assert_eq!(data.into_bytes(), vec![b'x'; 10]);
+    }
+
+    #[test]
+    fn apply_skips_compression_below_the_min_size_threshold() {
+        let config = CompressionConfig::new(vec![Encoding::Gzip]).min_size(1024);
+        let data = config.apply(Some("gzip"), Box::new(Payload(vec![b'x'; 10])));
+        assert_eq!(data.content_encoding(), None);
+        assert_eq!(data.into_bytes(), vec![b'x'; 10]);
+    }
+
+    #[test]
+    fn apply_compresses_when_encoding_is_accepted_and_size_clears_the_threshold() {
+        let config = CompressionConfig::new(vec![Encoding::Gzip]).min_size(0);
+        let raw = vec![b'x'; 64];
+        let data = config.apply(Some("gzip"), Box::new(Payload(raw.clone())));
+        assert_eq!(data.code(), 200);
+        assert_eq!(data.content_encoding(), Some("gzip"));
+        assert_ne!(data.into_bytes(), raw);
+    }
+}
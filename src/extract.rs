@@ -0,0 +1,335 @@
+use std::future::Future;
+use std::marker::PhantomData;
+
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+use serde::de::value::{Error as ValueError, MapDeserializer};
+
+use crate::proc::{Data, ExtractError, RequestContext, RouteHandle};
+
+/// Builds `Self` out of an inbound request, or fails with an `ExtractError` that
+/// short-circuits the handler. Mirrors actix-web's `FromRequest` and axum's
+/// `FromRequest`/`FromRequestParts` extractors.
+pub trait FromRequest: Sized {
+
+    fn from_request(req: &RequestContext) -> Result<Self, ExtractError>;
+}
+
+/// Deserializes a single path/query value into any scalar `T`, parsing
+/// numbers and `bool` from their string representation rather than requiring
+/// `T::deserialize` to be handed an already-typed value (which it never is —
+/// everything in the URL is a string).
+struct ScalarDeserializer<'a>(&'a str);
+
+macro_rules! deserialize_parsed {
+    ($method:ident, $visit:ident, $ty:ty) => {
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+            match self.0.parse::<$ty>() {
+                Ok(v) => visitor.$visit(v),
+                Err(_) => Err(de::Error::invalid_value(de::Unexpected::Str(self.0), &visitor)),
+            }
+        }
+    };
+}
+
+impl<'de> de::Deserializer<'de> for ScalarDeserializer<'de> {
+    type Error = ValueError;
+
+    deserialize_parsed!(deserialize_bool, visit_bool, bool);
+    deserialize_parsed!(deserialize_i8, visit_i8, i8);
+    deserialize_parsed!(deserialize_i16, visit_i16, i16);
+    deserialize_parsed!(deserialize_i32, visit_i32, i32);
+    deserialize_parsed!(deserialize_i64, visit_i64, i64);
+    deserialize_parsed!(deserialize_u8, visit_u8, u8);
+    deserialize_parsed!(deserialize_u16, visit_u16, u16);
+    deserialize_parsed!(deserialize_u32, visit_u32, u32);
+    deserialize_parsed!(deserialize_u64, visit_u64, u64);
+    deserialize_parsed!(deserialize_f32, visit_f32, f32);
+    deserialize_parsed!(deserialize_f64, visit_f64, f64);
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.0.into_deserializer().deserialize_any(visitor)
+    }
+
+    serde::forward_to_deserialize_any! {
+        char str string bytes byte_buf option unit unit_struct newtype_struct
+        seq tuple tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+/// A map value that deserializes through `ScalarDeserializer` rather than as a
+/// bare string, so `MapDeserializer::new(pairs)` lets struct fields be
+/// numeric or `bool`, not just `String`.
+struct ScalarValue<'a>(&'a str);
+
+impl<'de> IntoDeserializer<'de, ValueError> for ScalarValue<'de> {
+    type Deserializer = ScalarDeserializer<'de>;
+
+    fn into_deserializer(self) -> Self::Deserializer {
+        ScalarDeserializer(self.0)
+    }
+}
+
+/// Deserialize `T` from name/value pairs, parsing each value through
+/// `ScalarDeserializer` so numeric/bool fields work alongside `String` ones.
+/// Shared by `Path` and `Query`'s multi-value case.
+fn deserialize_pairs<T: DeserializeOwned>(pairs: Vec<(&str, &str)>) -> Result<T, ValueError> {
+    let pairs = pairs.into_iter().map(|(k, v)| (k, ScalarValue(v)));
+    T::deserialize(MapDeserializer::<_, ValueError>::new(pairs))
+}
+
+/// Deserializes `T` from the request's named path parameters
+/// (see `RequestContext::path_params`). When exactly one path parameter was
+/// captured, `T` may be a scalar (`Path<u64>`, `Path<String>`, ...) parsed
+/// from that single value directly; otherwise `T` must be a struct, whose
+/// fields are matched to the captures by name and may themselves be scalars
+/// (see `ScalarValue`). A tuple `T` receives each capture as a `(name, value)`
+/// pair, not as the bare value.
+#[derive(Debug)]
+pub struct Path<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for Path<T> {
+
+    fn from_request(req: &RequestContext) -> Result<Self, ExtractError> {
+        let pairs: Vec<(&str, &str)> = req.path_params().collect();
+        if pairs.len() == 1 {
+            let de = ScalarDeserializer(pairs[0].1);
+            if let Ok(v) = T::deserialize(de) {
+                return Ok(Path(v));
+            }
+        }
+        deserialize_pairs(pairs)
+            .map(Path)
+            .map_err(|e| ExtractError::new(format!("failed to extract path params: {}", e)))
+    }
+}
+
+/// Deserializes `T` from the request's parsed query parameters (see
+/// `RequestContext::params`). Parameters without a name are skipped; a
+/// parameter without a value is given the empty string.
+/// `T`'s fields may be scalars (`u64`, `bool`, ...) or `String`, mirroring
+/// actix-web's `web::Query`.
+#[derive(Debug)]
+pub struct Query<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for Query<T> {
+
+    fn from_request(req: &RequestContext) -> Result<Self, ExtractError> {
+        let pairs: Vec<(&str, &str)> = req.params().iter()
+            .filter_map(|p| {
+                let name = p.name(req)?;
+                let value = p.value(req).unwrap_or("");
+                Some((name, value))
+            })
+            .collect();
+        deserialize_pairs(pairs)
+            .map(Query)
+            .map_err(|e| ExtractError::new(format!("failed to extract query params: {}", e)))
+    }
+}
+
+/// Every query parameter as an owned `(name, value)` pair, unparsed. Always succeeds.
+#[derive(Debug)]
+pub struct AllParams(pub Vec<(String, String)>);
+
+impl FromRequest for AllParams {
+
+    fn from_request(req: &RequestContext) -> Result<Self, ExtractError> {
+        let pairs = req.params().iter()
+            .filter_map(|p| {
+                let name = p.name(req)?.to_owned();
+                let value = p.value(req).unwrap_or("").to_owned();
+                Some((name, value))
+            })
+            .collect();
+        Ok(AllParams(pairs))
+    }
+}
+
+/// Wraps an async function of one `FromRequest` argument as a `RouteHandle`.
+pub struct Handler1<F, A> {
+    func: F,
+    _marker: PhantomData<fn(A)>,
+}
+
+impl<F, A> Handler1<F, A> {
+
+    pub fn new(func: F) -> Self {
+        Handler1 { func, _marker: PhantomData }
+    }
+}
+
+#[async_trait]
+impl<F, Fut, A, D> RouteHandle for Handler1<F, A>
+    where
+        F: Fn(A) -> Fut + Send + Sync,
+        Fut: Future<Output=D> + Send,
+        A: FromRequest + Send,
+        D: Data + 'static {
+
+    async fn handle(&self, req: RequestContext) -> Box<dyn Data> {
+        match A::from_request(&req) {
+            Ok(a) => Box::new((self.func)(a).await),
+            Err(e) => Box::new(e.to_data()),
+        }
+    }
+}
+
+/// Wraps an async function of two `FromRequest` arguments as a `RouteHandle`.
+pub struct Handler2<F, A, B> {
+    func: F,
+    _marker: PhantomData<fn(A, B)>,
+}
+
+impl<F, A, B> Handler2<F, A, B> {
+
+    pub fn new(func: F) -> Self {
+        Handler2 { func, _marker: PhantomData }
+    }
+}
+
+#[async_trait]
+impl<F, Fut, A, B, D> RouteHandle for Handler2<F, A, B>
+    where
+        F: Fn(A, B) -> Fut + Send + Sync,
+        Fut: Future<Output=D> + Send,
+        A: FromRequest + Send,
+        B: FromRequest + Send,
+        D: Data + 'static {
+
+    async fn handle(&self, req: RequestContext) -> Box<dyn Data> {
+        let a = match A::from_request(&req) {
+            Ok(a) => a,
+            Err(e) => return Box::new(e.to_data()),
+        };
+        let b = match B::from_request(&req) {
+            Ok(b) => b,
+            Err(e) => return Box::new(e.to_data()),
+        };
+        Box::new((self.func)(a, b).await)
+    }
+}
+
+/// Wraps an async function of three `FromRequest` arguments as a `RouteHandle`.
+pub struct Handler3<F, A, B, C> {
+    func: F,
+    _marker: PhantomData<fn(A, B, C)>,
+}
+
+impl<F, A, B, C> Handler3<F, A, B, C> {
+
+    pub fn new(func: F) -> Self {
+        Handler3 { func, _marker: PhantomData }
+    }
+}
+
+#[async_trait]
+impl<F, Fut, A, B, C, D> RouteHandle for Handler3<F, A, B, C>
+    where
+        F: Fn(A, B, C) -> Fut + Send + Sync,
+        Fut: Future<Output=D> + Send,
+        A: FromRequest + Send,
+        B: FromRequest + Send,
+        C: FromRequest + Send,
+        D: Data + 'static {
+
+    async fn handle(&self, req: RequestContext) -> Box<dyn Data> {
+        let a = match A::from_request(&req) {
+            Ok(a) => a,
+            Err(e) => return Box::new(e.to_data()),
+        };
+        let b = match B::from_request(&req) {
+            Ok(b) => b,
+            Err(e) => return Box::new(e.to_data()),
+        };
+        let c = match C::from_request(&req) {
+            Ok(c) => c,
+            Err(e) => return Box::new(e.to_data()),
+        };
+        Box::new((self.func)(a, b, c).await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proc::{Method, StrRef};
+
+    fn req_with_path_params(url: &str, params: &[(&str, &str)]) -> RequestContext {
+        let mut req = RequestContext::new(url.to_string(), Method::Get, Vec::new());
+        let params = {
+            let original = req.original_url();
+            params.iter()
+                .map(|(n, v)| {
+                    let start = original.find(v).expect("test value must appear in the url");
+                    let slice = &original[start..start + v.len()];
+                    (n.to_string(), StrRef::from_part(original, slice))
+                })
+                .collect()
+        };
+        req.set_path_params(params);
+        req
+    }
+
+    #[test]
+    fn path_extracts_a_bare_scalar_from_a_single_capture() {
+        let req = req_with_path_params("/users/42", &[("id", "42")]);
+        let Path(id): Path<u64> = Path::from_request(&req).unwrap();
+        assert_eq!(id, 42);
+    }
+
+    // Multiple captures go through `deserialize_pairs`, so struct fields may be
+    // scalars too; this one just uses `String` fields since that's the common case.
+    #[derive(serde::Deserialize, Debug, PartialEq)]
+    struct UserParams {
+        id: String,
+        slug: String,
+    }
+
+    #[test]
+    fn path_extracts_a_struct_from_multiple_captures() {
+        let req = req_with_path_params("/users/42/rust-fan", &[("id", "42"), ("slug", "rust-fan")]);
+        let Path(params): Path<UserParams> = Path::from_request(&req).unwrap();
+        assert_eq!(params, UserParams { id: "42".to_string(), slug: "rust-fan".to_string() });
+    }
+
+    #[test]
+    fn path_reports_extract_error_on_mismatched_scalar() {
+        let req = req_with_path_params("/users/not-a-number", &[("id", "not-a-number")]);
+        let result: Result<Path<u64>, ExtractError> = Path::from_request(&req);
+        assert!(result.is_err());
+    }
+
+    fn req_with_query(url: &str) -> RequestContext {
+        RequestContext::new(url.to_string(), Method::Get, Vec::new())
+    }
+
+    #[derive(serde::Deserialize, Debug, PartialEq)]
+    struct Filter {
+        page: u64,
+        archived: bool,
+    }
+
+    #[test]
+    fn query_deserializes_numeric_and_bool_fields() {
+        let req = req_with_query("/posts?page=2&archived=true");
+        let Query(filter): Query<Filter> = Query::from_request(&req).unwrap();
+        assert_eq!(filter, Filter { page: 2, archived: true });
+    }
+
+    #[test]
+    fn query_reports_extract_error_on_mismatched_scalar() {
+        let req = req_with_query("/posts?page=not-a-number&archived=true");
+        let result: Result<Query<Filter>, ExtractError> = Query::from_request(&req);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn all_params_collects_every_query_pair_unparsed() {
+        let req = req_with_query("/posts?page=2&archived=true");
+        let AllParams(pairs) = AllParams::from_request(&req).unwrap();
+        assert_eq!(pairs, vec![
+            ("page".to_string(), "2".to_string()),
+            ("archived".to_string(), "true".to_string()),
+        ]);
+    }
+}
@@ -2,6 +2,9 @@ extern crate haruhi_macro;
 #[macro_use]
 extern crate async_trait;
 extern crate regex;
+extern crate serde;
+extern crate flate2;
+extern crate brotli;
 
 pub use haruhi_macro::*;
 
@@ -9,6 +12,10 @@ pub mod route;
 
 pub mod proc;
 
+pub mod extract;
+
+pub mod compress;
+
 pub type ResponseCode = u16;
 
 #[cfg(test)]
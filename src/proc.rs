@@ -4,50 +4,55 @@ use std::pin::Pin;
 use std::cell::Cell;
 use std::sync::Mutex;
 
+/// HTTP request method.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Method {
+    Get,
+    Post,
+    Put,
+    Patch,
+    Delete,
+    Head,
+    Options,
+    Connect,
+    Trace,
+}
+
 #[derive(Debug)]
 pub struct RequestContext {
 
     url_info: UrlInfo,
-}
 
-/// Pointer to string slice inside of a `UrlInfo`.
-#[derive(Clone, Copy)]
-pub struct StrRef(*const u8, usize);
+    method: Method,
 
-impl From<(*const u8, usize)> for StrRef {
+    headers: Vec<(String, String)>,
 
-    fn from(tuple: (*const u8, usize)) -> Self {
-        StrRef(tuple.0, tuple.1)
-    }
+    /// Named path parameters captured by the matching route's regex, e.g. `:id`
+    /// from `(?P<id>[0-9]+)`. Empty until a `RouteMatchGroup` populates it.
+    path_params: Vec<(String, StrRef)>,
 }
 
-impl From<&[u8]> for StrRef {
-
-    fn from(slice: &[u8]) -> Self {
-        (slice.as_ptr(), slice.len()).into()
-    }
-}
+/// Byte range of a string slice relative to the start of the owning `UrlInfo::original_url`.
+/// Resolve it back into a `&str` with `RequestContext::resolve`.
+#[derive(Debug, Clone, Copy)]
+pub struct StrRef {
 
-impl AsRef<str> for StrRef {
+    start: usize,
 
-    fn as_ref(&self) -> &str {
-        unsafe {
-            let slice = std::slice::from_raw_parts(self.0, self.1);
-            std::str::from_utf8_unchecked(slice)
-        }
-    }
+    len: usize,
 }
 
-impl Debug for StrRef {
+impl StrRef {
 
-    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
-        self.as_ref().fmt(f)
+    /// Build a `StrRef` describing `part`'s position within `original`.
+    /// `part` must be a substring slice of `original` (e.g. produced by `split`),
+    /// so its offset is well-defined.
+    pub(crate) fn from_part(original: &str, part: &str) -> Self {
+        let start = part.as_ptr() as usize - original.as_ptr() as usize;
+        StrRef { start, len: part.len() }
     }
 }
 
-unsafe impl Send for StrRef {}
-unsafe impl Sync for StrRef {}
-
 struct UrlInfo {
 
     /// Original URL as it was delivered to the server.
@@ -80,18 +85,74 @@ impl Debug for UrlInfo {
             parts: {:?}}}",
             self.original_url,
             unsafe { &*self.params.as_ptr() },
-            self.path.get().unwrap(),
-            unsafe { &*self.parts.as_ptr() },
+            self.path.get().map(|r| self.resolve(r)),
+            unsafe { &*self.parts.as_ptr() }.iter()
+                .map(|r| self.resolve(*r))
+                .collect::<Vec<_>>(),
         )
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+/// A parsed query parameter's name or value. Borrowed from `original_url` in the
+/// common case; owned only when percent-decoding had to allocate.
+#[derive(Debug, Clone)]
+pub enum ParamValue {
+    Borrowed(StrRef),
+    Owned(String),
+}
+
+#[derive(Debug, Clone)]
 pub struct Param {
 
-    name: Option<StrRef>,
+    name: Option<ParamValue>,
+
+    value: Option<ParamValue>,
+}
+
+impl Param {
+
+    pub fn name<'a>(&'a self, req: &'a RequestContext) -> Option<&'a str> {
+        self.name.as_ref().map(|v| req.resolve_value(v))
+    }
+
+    pub fn value<'a>(&'a self, req: &'a RequestContext) -> Option<&'a str> {
+        self.value.as_ref().map(|v| req.resolve_value(v))
+    }
+}
+
+/// Percent-decode `part`, replacing `+` with space first as query strings do, if
+/// it contains any escapes; otherwise borrow it as-is. Falls back to borrowing the
+/// raw (still-encoded) slice on malformed escapes or non-UTF-8 output.
+fn decode_param(original: &str, part: &str) -> ParamValue {
+    if !part.contains('%') && !part.contains('+') {
+        return ParamValue::Borrowed(StrRef::from_part(original, part));
+    }
+
+    let mut bytes = Vec::with_capacity(part.len());
+    let mut iter = part.bytes();
+    while let Some(b) = iter.next() {
+        match b {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = match (iter.next(), iter.next()) {
+                    (Some(hi), Some(lo)) => [hi, lo],
+                    _ => return ParamValue::Borrowed(StrRef::from_part(original, part)),
+                };
+                match std::str::from_utf8(&hex).ok()
+                    .filter(|h| h.bytes().all(|c| c.is_ascii_hexdigit()))
+                    .and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                    Some(byte) => bytes.push(byte),
+                    None => return ParamValue::Borrowed(StrRef::from_part(original, part)),
+                }
+            }
+            other => bytes.push(other),
+        }
+    }
 
-    value: Option<StrRef>,
+    match String::from_utf8(bytes) {
+        Ok(decoded) => ParamValue::Owned(decoded),
+        Err(_) => ParamValue::Borrowed(StrRef::from_part(original, part)),
+    }
 }
 
 impl UrlInfo {
@@ -106,6 +167,11 @@ impl UrlInfo {
         }
     }
 
+    /// Resolve a `StrRef` previously produced for this `UrlInfo` into its backing slice.
+    fn resolve(&self, r: StrRef) -> &str {
+        &self.original_url[r.start..r.start + r.len]
+    }
+
     /// Force lazy parsing of the URL to get the parameters and url path parts.
     pub fn lazy_parse(&self) {
         if self.original_url.is_empty() {
@@ -115,41 +181,40 @@ impl UrlInfo {
 
         let _lock = self.parse_mutex.lock().unwrap();
 
-        let path_parts = self.original_url.split('/');
+        let original: &str = &self.original_url;
+
+        // Split off the query on the *first* '?' before ever touching '/', so a
+        // query value that itself contains a slash (e.g. `?file=/etc/passwd`)
+        // doesn't get mistaken for extra path segments.
+        let mut path_and_query = original.splitn(2, '?');
+        let path = path_and_query.next().unwrap();
+
+        let path_parts: Vec<&str> = path.split('/').collect();
         let parts: Vec<StrRef> = {
-            let mut vec = Vec::with_capacity(path_parts.size_hint().0);
-            for part in path_parts {
-                vec.push(part.as_bytes().into());
+            let mut vec = Vec::with_capacity(path_parts.len());
+            for part in &path_parts {
+                vec.push(StrRef::from_part(original, part));
             }
             vec
         };
-        let last = parts.last().unwrap().clone();
-        let mut params_parts = last.as_ref().split('?');
-        let path = params_parts.next().unwrap();
-        let params = {
-            let mut vec = Vec::with_capacity(params_parts.size_hint().0);
-            for part in params_parts {
-                let starts_with_eq = part.starts_with('=');
-                let mut split = part.split('=');
-                let ptr = |v: &str| v.as_bytes().into();
-                let p = if starts_with_eq {
-                    Param {
-                        name: None,
-                        value: split.next().map(ptr),
-                    }
-                } else {
+        let params = match path_and_query.next() {
+            Some(query) => query.split('&')
+                .filter(|pair| !pair.is_empty())
+                .map(|pair| {
+                    let mut split = pair.splitn(2, '=');
+                    let name = split.next().unwrap_or("");
+                    let value = split.next();
                     Param {
-                        name: split.next().map(ptr),
-                        value: split.next().map(ptr),
+                        name: Some(decode_param(original, name)),
+                        value: value.map(|v| decode_param(original, v)),
                     }
-                };
-                vec.push(p);
-            }
-            vec
+                })
+                .collect(),
+            None => Vec::new(),
         };
 
         self.params.set(params);
-        self.path.set(Some(path.as_bytes().into()));
+        self.path.set(Some(StrRef::from_part(original, path)));
         self.parts.set(parts);
     }
 }
@@ -158,10 +223,39 @@ unsafe impl Sync for UrlInfo {}
 
 impl RequestContext {
 
+    /// Build a context for the given raw URL, method and headers, with no path
+    /// parameters captured yet.
+    pub fn new(original_url: String, method: Method, headers: Vec<(String, String)>) -> Self {
+        RequestContext {
+            url_info: UrlInfo {
+                original_url: Pin::new(original_url),
+                parse_mutex: Mutex::new(()),
+                params: Cell::new(Vec::new()),
+                path: Cell::new(None),
+                parts: Cell::new(Vec::new()),
+            },
+            method,
+            headers,
+            path_params: Vec::new(),
+        }
+    }
+
     pub fn original_url(&self) -> &str {
         &self.url_info.original_url
     }
 
+    pub fn method(&self) -> Method {
+        self.method
+    }
+
+    /// Value of the first request header matching `name`, compared case-insensitively
+    /// as header names are.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
     pub fn params(&self) -> &Vec<Param> {
         self.url_info.parse_if_needed();
         unsafe { &*self.url_info.params.as_ptr() }
@@ -176,6 +270,40 @@ impl RequestContext {
         self.url_info.parse_if_needed();
         self.url_info.path.get().unwrap()
     }
+
+    /// Resolve a `StrRef` returned by `path()` or `parts()` into the string slice
+    /// it refers to.
+    pub fn resolve(&self, r: StrRef) -> &str {
+        self.url_info.resolve(r)
+    }
+
+    /// Resolve a `Param`'s name or value (used by `Param::name`/`Param::value`)
+    /// into the string slice it refers to, whether borrowed from the URL or
+    /// percent-decoded.
+    fn resolve_value<'a>(&'a self, v: &'a ParamValue) -> &'a str {
+        match v {
+            ParamValue::Borrowed(r) => self.resolve(*r),
+            ParamValue::Owned(s) => s.as_str(),
+        }
+    }
+
+    /// Value of the named path parameter captured by the matching route, if any.
+    pub fn path_param(&self, name: &str) -> Option<&str> {
+        self.path_params.iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, r)| self.resolve(*r))
+    }
+
+    /// Iterate over every named path parameter captured by the matching route.
+    pub fn path_params(&self) -> impl Iterator<Item=(&str, &str)> {
+        self.path_params.iter().map(move |(n, r)| (n.as_str(), self.resolve(*r)))
+    }
+
+    /// Replace the captured path parameters. Called by `RouteMatchGroup::find`
+    /// once a route's regex has matched, before `dispatch` runs the handle.
+    pub(crate) fn set_path_params(&mut self, params: Vec<(String, StrRef)>) {
+        self.path_params = params;
+    }
 }
 
 #[derive(Debug)]
@@ -391,5 +519,110 @@ pub trait Data: Debug {
 
     fn code(&self) -> ResponseCode;
 
-    fn into_bytes(self) -> Vec<u8>;
+    fn into_bytes(self: Box<Self>) -> Vec<u8>;
+
+    /// `Content-Encoding` of the bytes returned by `into_bytes`, if they were
+    /// compressed (see `crate::compress::CompressionConfig`).
+    fn content_encoding(&self) -> Option<&'static str> {
+        None
+    }
+}
+
+/// Failure produced by a `crate::extract::FromRequest` extractor. Extraction runs
+/// before any `Process`/`ContextBundle` exists for the request, so there is
+/// nothing yet for this to `fix`/`amend` into — `RouteHandle` wrappers (see
+/// `Handler1`/`Handler2`/`Handler3`) instead turn it straight into a `400`
+/// response via `to_data`.
+#[derive(Debug, Clone)]
+pub struct ExtractError {
+    message: String,
+}
+
+impl ExtractError {
+
+    pub fn new(message: impl Into<String>) -> Self {
+        ExtractError { message: message.into() }
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn to_data(&self) -> ExtractErrorData {
+        ExtractErrorData { message: self.message.clone() }
+    }
+}
+
+#[derive(Debug)]
+pub struct ExtractErrorData {
+    message: String,
+}
+
+impl Data for ExtractErrorData {
+
+    fn code(&self) -> ResponseCode {
+        400
+    }
+
+    fn into_bytes(self: Box<Self>) -> Vec<u8> {
+        self.message.into_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ctx(url: &str) -> RequestContext {
+        RequestContext::new(url.to_string(), Method::Get, Vec::new())
+    }
+
+    #[test]
+    fn query_value_containing_a_slash_does_not_eat_the_path() {
+        let req = ctx("/dl?file=/etc/passwd");
+        assert_eq!(req.resolve(req.path()), "/dl");
+        let params = req.params();
+        assert_eq!(params.len(), 1);
+        assert_eq!(params[0].name(&req), Some("file"));
+        assert_eq!(params[0].value(&req), Some("/etc/passwd"));
+    }
+
+    #[test]
+    fn multiple_ampersand_separated_params_are_parsed() {
+        let req = ctx("/search?q=rust&page=2&empty");
+        let params = req.params();
+        assert_eq!(params.len(), 3);
+        assert_eq!(params[0].name(&req), Some("q"));
+        assert_eq!(params[0].value(&req), Some("rust"));
+        assert_eq!(params[1].name(&req), Some("page"));
+        assert_eq!(params[1].value(&req), Some("2"));
+        assert_eq!(params[2].name(&req), Some("empty"));
+        assert_eq!(params[2].value(&req), None);
+    }
+
+    #[test]
+    fn percent_and_plus_decoding() {
+        let req = ctx("/search?q=hello%20world+foo");
+        let params = req.params();
+        assert_eq!(params[0].value(&req), Some("hello world foo"));
+    }
+
+    #[test]
+    fn path_without_query_has_no_params() {
+        let req = ctx("/users/42");
+        assert_eq!(req.resolve(req.path()), "/users/42");
+        assert!(req.params().is_empty());
+    }
+
+    #[test]
+    fn str_refs_stay_valid_after_moving_the_request_context() {
+        let req = ctx("/users/42?verbose=true");
+        let path = req.path();
+        // Box (and thus move) the context: `StrRef` holds byte offsets into
+        // `original_url` rather than pointers, so this doesn't invalidate the
+        // ones already handed out.
+        let req = Box::new(req);
+        assert_eq!(req.resolve(path), "/users/42");
+        assert_eq!(req.params()[0].value(&req), Some("true"));
+    }
 }
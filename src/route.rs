@@ -1,4 +1,6 @@
-use crate::proc::{RouteHandle, RequestContext};
+use crate::compress::CompressionConfig;
+use crate::proc::{Data, Method, RouteHandle, RequestContext, StrRef};
+use crate::ResponseCode;
 use regex::Regex;
 
 type Handle = Box<dyn RouteHandle>;
@@ -6,26 +8,369 @@ type Handle = Box<dyn RouteHandle>;
 /// List of routes that are grouped and can be enabled/disabled all at once.
 pub struct RouteMatchGroup {
     arr: Vec<RouteMatch>,
+    middleware: Vec<Box<dyn Middleware>>,
+    /// Returned when no route's regex matches the request. Emits `404 Not Found`
+    /// by default; override with `set_default_handle`.
+    default_handle: Handle,
+    /// When set, negotiates and applies response compression for every route in
+    /// this group, as the final step after middleware `after` hooks.
+    compression: Option<CompressionConfig>,
 }
 
 pub struct RouteMatch {
+    method: Method,
     regex: Regex,
     handle: Handle,
 }
 
+/// Outcome of matching a request against a `RouteMatchGroup`'s routes.
+enum Matched<'a> {
+    /// A route's regex and method both matched; its path params are already
+    /// stored on the `RequestContext`.
+    Found(&'a Handle),
+    /// A route's regex matched, but not for the request's method.
+    MethodNotAllowed,
+    /// No route's regex matched.
+    NotFound,
+}
+
 impl RouteMatchGroup {
 
-    /// Handle for given
-    pub fn handle_for(&self, req: RequestContext) -> Option<&Handle> {
+    pub fn new() -> Self {
+        RouteMatchGroup {
+            arr: Vec::new(),
+            middleware: Vec::new(),
+            default_handle: Box::new(NotFoundHandle),
+            compression: None,
+        }
+    }
+
+    /// Enable response compression for every route in this group, negotiated from
+    /// each request's `Accept-Encoding`.
+    pub fn set_compression(&mut self, compression: CompressionConfig) -> &mut Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Register a route, matched when both `regex` and `method` agree with the request.
+    pub fn add_route(&mut self, method: Method, regex: Regex, handle: impl RouteHandle + 'static) -> &mut Self {
+        self.arr.push(RouteMatch { method, regex, handle: Box::new(handle) });
+        self
+    }
+
+    /// Override the handle returned when no route matches. Defaults to a plain
+    /// `404 Not Found`.
+    pub fn set_default_handle(&mut self, handle: impl RouteHandle + 'static) -> &mut Self {
+        self.default_handle = Box::new(handle);
+        self
+    }
+
+    /// Register a middleware. Middleware runs in registration order on the way in
+    /// (`before`) and in reverse on the way out (`after`), for every route in this
+    /// group.
+    pub fn use_middleware(&mut self, middleware: impl Middleware + 'static) -> &mut Self {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Match `req` against this group's routes, by path regex and method, storing
+    /// any named path parameters onto `req` when a route is found.
+    fn find(&self, req: &mut RequestContext) -> Matched {
+        let mut path_matched = false;
         for i in &self.arr {
-            let url = req.path();
-            if i.regex.is_match(url.as_ref()) {
-                return Some(&i.handle);
+            let captured = {
+                let path = req.path();
+                let url = req.resolve(path);
+                i.regex.captures(url).map(|captures| {
+                    let original = req.original_url();
+                    i.regex.capture_names()
+                        .flatten()
+                        .filter_map(|name| captures.name(name)
+                            .map(|m| (name.to_string(), StrRef::from_part(original, m.as_str()))))
+                        .collect::<Vec<_>>()
+                })
+            };
+            if let Some(params) = captured {
+                path_matched = true;
+                if i.method == req.method() {
+                    req.set_path_params(params);
+                    return Matched::Found(&i.handle);
+                }
             }
         }
-        None
+        if path_matched {
+            Matched::MethodNotAllowed
+        } else {
+            Matched::NotFound
+        }
+    }
+
+    /// Match `req` against this group's routes and run it through the registered
+    /// middleware: every `before` hook in registration order, then the matched
+    /// `RouteHandle::handle` (or `default_handle` on a `404`), then every `after`
+    /// hook in reverse order. A `before` hook that returns `Flow::Abort` skips the
+    /// handle and unwinds through the `after` hooks of the middleware that already
+    /// ran. A `405 Method Not Allowed` bypasses both the handle and the middleware,
+    /// since no route owns the request.
+    pub async fn dispatch(&self, mut req: RequestContext) -> Box<dyn Data> {
+        match self.find(&mut req) {
+            Matched::Found(handle) => self.run(handle, req).await,
+            Matched::NotFound => self.run(&self.default_handle, req).await,
+            Matched::MethodNotAllowed => Box::new(MethodNotAllowedData),
+        }
+    }
+
+    async fn run(&self, handle: &Handle, mut req: RequestContext) -> Box<dyn Data> {
+        let mut entered = 0;
+        let mut short_circuit = None;
+        for m in &self.middleware {
+            entered += 1;
+            match m.before(&mut req).await {
+                Flow::Continue => {}
+                Flow::Abort(resp) => {
+                    short_circuit = Some(resp);
+                    break;
+                }
+            }
+        }
+
+        let accept_encoding = self.compression.is_some()
+            .then(|| req.header("Accept-Encoding").map(str::to_owned))
+            .flatten();
+
+        let mut resp = match short_circuit {
+            Some(resp) => resp,
+            None => handle.handle(req).await,
+        };
+
+        for m in self.middleware[..entered].iter().rev() {
+            resp = m.after(resp).await;
+        }
+
+        if let Some(compression) = &self.compression {
+            resp = compression.apply(accept_encoding.as_deref(), resp);
+        }
+
+        resp
+    }
+}
+
+struct NotFoundHandle;
+
+#[async_trait]
+impl RouteHandle for NotFoundHandle {
+
+    async fn handle(&self, _req: RequestContext) -> Box<dyn Data> {
+        Box::new(NotFoundData)
+    }
+}
+
+#[derive(Debug)]
+struct NotFoundData;
+
+impl Data for NotFoundData {
+
+    fn code(&self) -> ResponseCode {
+        404
+    }
+
+    fn into_bytes(self: Box<Self>) -> Vec<u8> {
+        Vec::new()
     }
 }
 
-// TODO: at init of server there always should be defined 'default' handle for unmatched routes
-// that will normally just emit '404 not found' error
+#[derive(Debug)]
+struct MethodNotAllowedData;
+
+impl Data for MethodNotAllowedData {
+
+    fn code(&self) -> ResponseCode {
+        405
+    }
+
+    fn into_bytes(self: Box<Self>) -> Vec<u8> {
+        Vec::new()
+    }
+}
+
+/// Outcome of a `Middleware::before` hook: either let the request continue to the
+/// next middleware (and eventually the matched `RouteHandle`), or short-circuit
+/// straight to a response.
+pub enum Flow {
+    Continue,
+    Abort(Box<dyn Data>),
+}
+
+/// Cross-cutting logic that runs around every route in a `RouteMatchGroup`
+/// (logging, auth, header injection, ...). Modeled on actix-web's
+/// `Started`/`Response` hooks and warp's wrapping filters.
+#[async_trait]
+pub trait Middleware: Send + Sync {
+
+    /// Runs before the matched `RouteHandle`. Return `Flow::Abort` to skip it.
+    async fn before(&self, req: &mut RequestContext) -> Flow {
+        let _ = req;
+        Flow::Continue
+    }
+
+    /// Runs after the matched `RouteHandle` (or after an aborting `before` hook),
+    /// in reverse registration order, to post-process the response.
+    async fn after(&self, resp: Box<dyn Data>) -> Box<dyn Data> {
+        resp
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proc::RequestContext;
+
+    struct DummyHandle;
+
+    #[async_trait]
+    impl RouteHandle for DummyHandle {
+
+        async fn handle(&self, _req: RequestContext) -> Box<dyn Data> {
+            unreachable!("not exercised by these tests")
+        }
+    }
+
+    fn group() -> RouteMatchGroup {
+        let mut g = RouteMatchGroup::new();
+        g.add_route(Method::Get, Regex::new(r"^/users/(?P<id>\d+)$").unwrap(), DummyHandle);
+        g
+    }
+
+    #[test]
+    fn matches_full_multi_segment_path_and_captures_param() {
+        let group = group();
+        let mut req = RequestContext::new("/users/123".to_string(), Method::Get, Vec::new());
+        match group.find(&mut req) {
+            Matched::Found(_) => {}
+            _ => panic!("expected route to match"),
+        }
+        assert_eq!(req.path_param("id"), Some("123"));
+    }
+
+    #[test]
+    fn wrong_method_on_matching_path_is_method_not_allowed() {
+        let group = group();
+        let mut req = RequestContext::new("/users/123".to_string(), Method::Post, Vec::new());
+        match group.find(&mut req) {
+            Matched::MethodNotAllowed => {}
+            _ => panic!("expected 405"),
+        }
+    }
+
+    #[test]
+    fn unmatched_path_is_not_found() {
+        let group = group();
+        let mut req = RequestContext::new("/nope".to_string(), Method::Get, Vec::new());
+        match group.find(&mut req) {
+            Matched::NotFound => {}
+            _ => panic!("expected 404"),
+        }
+    }
+
+    // Minimal single-threaded executor: these handler/middleware futures never
+    // actually wait on external IO, so a busy-poll with a no-op waker is enough
+    // to drive them to completion without pulling in an async runtime.
+    fn block_on<F: std::future::Future>(fut: F) -> F::Output {
+        use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+        fn noop(_: *const ()) {}
+        fn clone(_: *const ()) -> RawWaker { RawWaker::new(std::ptr::null(), &VTABLE) }
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+
+        let waker = unsafe { Waker::from_raw(RawWaker::new(std::ptr::null(), &VTABLE)) };
+        let mut cx = Context::from_waker(&waker);
+        let mut fut = Box::pin(fut);
+        loop {
+            match fut.as_mut().poll(&mut cx) {
+                Poll::Ready(v) => return v,
+                Poll::Pending => continue,
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    struct TestData(ResponseCode);
+
+    impl Data for TestData {
+
+        fn code(&self) -> ResponseCode {
+            self.0
+        }
+
+        fn into_bytes(self: Box<Self>) -> Vec<u8> {
+            Vec::new()
+        }
+    }
+
+    struct OkHandle;
+
+    #[async_trait]
+    impl RouteHandle for OkHandle {
+
+        async fn handle(&self, _req: RequestContext) -> Box<dyn Data> {
+            Box::new(TestData(200))
+        }
+    }
+
+    struct RecordingMiddleware {
+        name: &'static str,
+        abort: bool,
+        log: std::sync::Arc<std::sync::Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl Middleware for RecordingMiddleware {
+
+        async fn before(&self, _req: &mut RequestContext) -> Flow {
+            self.log.lock().unwrap().push(format!("before:{}", self.name));
+            if self.abort {
+                Flow::Abort(Box::new(TestData(503)))
+            } else {
+                Flow::Continue
+            }
+        }
+
+        async fn after(&self, resp: Box<dyn Data>) -> Box<dyn Data> {
+            self.log.lock().unwrap().push(format!("after:{}", self.name));
+            resp
+        }
+    }
+
+    #[test]
+    fn middleware_runs_before_in_order_and_after_in_reverse() {
+        let log = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut group = RouteMatchGroup::new();
+        group.add_route(Method::Get, Regex::new(r"^/ping$").unwrap(), OkHandle);
+        group.use_middleware(RecordingMiddleware { name: "outer", abort: false, log: log.clone() });
+        group.use_middleware(RecordingMiddleware { name: "inner", abort: false, log: log.clone() });
+
+        let req = RequestContext::new("/ping".to_string(), Method::Get, Vec::new());
+        let resp = block_on(group.dispatch(req));
+
+        assert_eq!(resp.code(), 200);
+        let recorded = log.lock().unwrap().clone();
+        assert_eq!(recorded, vec!["before:outer", "before:inner", "after:inner", "after:outer"]);
+    }
+
+    #[test]
+    fn middleware_abort_short_circuits_handle_but_unwinds_entered_afters() {
+        let log = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut group = RouteMatchGroup::new();
+        group.add_route(Method::Get, Regex::new(r"^/ping$").unwrap(), OkHandle);
+        group.use_middleware(RecordingMiddleware { name: "outer", abort: false, log: log.clone() });
+        group.use_middleware(RecordingMiddleware { name: "aborting", abort: true, log: log.clone() });
+        group.use_middleware(RecordingMiddleware { name: "never", abort: false, log: log.clone() });
+
+        let req = RequestContext::new("/ping".to_string(), Method::Get, Vec::new());
+        let resp = block_on(group.dispatch(req));
+
+        assert_eq!(resp.code(), 503);
+        let recorded = log.lock().unwrap().clone();
+        assert_eq!(recorded, vec!["before:outer", "before:aborting", "after:aborting", "after:outer"]);
+    }
+}